@@ -0,0 +1,243 @@
+use crate::config::apis::AuthProviderConfig;
+use crate::{Error, Result};
+
+use std::collections::HashMap;
+
+/// Resolve a bearer token from a kubeconfig `auth-provider` block.
+///
+/// Returns `Ok(None)` for providers we don't know how to handle so the caller
+/// can fall through to the other credential sources. A freshly minted token is
+/// written back into the provider's own config map only so the refresh logic in
+/// this call has a consistent view; the map is local to `new_from_kube_config`
+/// and is not persisted, so every load re-resolves the credential.
+pub async fn token(provider: &mut AuthProviderConfig) -> Result<Option<String>> {
+    match provider.name.as_str() {
+        "oidc" => oidc_token(&mut provider.config).await.map(Some),
+        "gcp" => gcp_token(&mut provider.config).map(Some),
+        "azure" => azure_token(&mut provider.config).await.map(Some),
+        other => {
+            debug!("ignoring unsupported auth-provider '{}'", other);
+            Ok(None)
+        }
+    }
+}
+
+/// Return the current `id-token`, refreshing it through an OAuth2
+/// refresh-token grant when the JWT has expired.
+async fn oidc_token(config: &mut HashMap<String, String>) -> Result<String> {
+    if let Some(id_token) = config.get("id-token") {
+        if !is_expired(id_token)? {
+            return Ok(id_token.clone());
+        }
+    }
+
+    let refresh_token = config
+        .get("refresh-token")
+        .ok_or_else(|| Error::KubeConfig("oidc auth-provider is missing refresh-token".into()))?
+        .clone();
+    let client_id = config
+        .get("client-id")
+        .ok_or_else(|| Error::KubeConfig("oidc auth-provider is missing client-id".into()))?
+        .clone();
+    let issuer = config
+        .get("idp-issuer-url")
+        .ok_or_else(|| Error::KubeConfig("oidc auth-provider is missing idp-issuer-url".into()))?
+        .clone();
+    let client_secret = config.get("client-secret").cloned().unwrap_or_default();
+
+    let id_token = refresh_oidc_token(&issuer, &client_id, &client_secret, &refresh_token).await?;
+    config.insert("id-token".into(), id_token.clone());
+    Ok(id_token)
+}
+
+/// Perform the refresh-token grant against the issuer's discovered token endpoint.
+async fn refresh_oidc_token(
+    issuer: &str,
+    client_id: &str,
+    client_secret: &str,
+    refresh_token: &str,
+) -> Result<String> {
+    let token_endpoint = discover_token_endpoint(issuer).await?;
+
+    let params = [
+        ("grant_type", "refresh_token"),
+        ("refresh_token", refresh_token),
+        ("client_id", client_id),
+        ("client_secret", client_secret),
+    ];
+    let res: HashMap<String, serde_json::Value> = reqwest::Client::new()
+        .post(&token_endpoint)
+        .form(&params)
+        .send()
+        .await
+        .map_err(|e| Error::KubeConfig(format!("oidc token refresh request failed: {}", e)))?
+        .json()
+        .await
+        .map_err(|e| Error::KubeConfig(format!("oidc token refresh response was not json: {}", e)))?;
+
+    res.get("id_token")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_owned())
+        .ok_or_else(|| Error::KubeConfig("oidc token refresh response did not contain an id_token".into()))
+}
+
+/// Read the `token_endpoint` out of the issuer's OpenID discovery document.
+async fn discover_token_endpoint(issuer: &str) -> Result<String> {
+    let url = format!("{}/.well-known/openid-configuration", issuer.trim_end_matches('/'));
+    let doc: HashMap<String, serde_json::Value> = reqwest::get(&url)
+        .await
+        .map_err(|e| Error::KubeConfig(format!("oidc discovery request failed: {}", e)))?
+        .json()
+        .await
+        .map_err(|e| Error::KubeConfig(format!("oidc discovery document was not json: {}", e)))?;
+
+    doc.get("token_endpoint")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_owned())
+        .ok_or_else(|| Error::KubeConfig("oidc discovery document has no token_endpoint".into()))
+}
+
+/// Return the cached gcp `access-token` while it is still valid, otherwise
+/// execute the gcloud helper described by the provider config and pull the
+/// token (and its expiry) out of the JSON it prints using the configured
+/// `token-key`/`expiry-key` paths.
+fn gcp_token(config: &mut HashMap<String, String>) -> Result<String> {
+    if let Some(access_token) = config.get("access-token") {
+        if !access_token.is_empty() && !gcp_expired(config.get("expiry").map(String::as_str)) {
+            return Ok(access_token.clone());
+        }
+    }
+
+    let cmd = config
+        .get("cmd-path")
+        .ok_or_else(|| Error::KubeConfig("gcp auth-provider is missing cmd-path".into()))?;
+    let args = config
+        .get("cmd-args")
+        .map(|a| a.split_whitespace().map(String::from).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let out = std::process::Command::new(cmd)
+        .args(&args)
+        .output()
+        .map_err(|e| Error::KubeConfig(format!("failed to run gcp auth-provider command: {}", e)))?;
+    if !out.status.success() {
+        return Err(Error::KubeConfig(format!(
+            "gcp auth-provider command failed: {}",
+            String::from_utf8_lossy(&out.stderr)
+        )));
+    }
+
+    let json: serde_json::Value = serde_json::from_slice(&out.stdout)
+        .map_err(|e| Error::KubeConfig(format!("gcp auth-provider output was not json: {}", e)))?;
+    let token_key = config
+        .get("token-key")
+        .cloned()
+        .unwrap_or_else(|| "{.access_token}".into());
+    let access_token = extract_key(&json, &token_key)
+        .ok_or_else(|| Error::KubeConfig(format!("gcp auth-provider output had no value at {}", token_key)))?;
+
+    let expiry_key = config
+        .get("expiry-key")
+        .cloned()
+        .unwrap_or_else(|| "{.token_expiry}".into());
+    if let Some(expiry) = extract_key(&json, &expiry_key) {
+        config.insert("expiry".into(), expiry);
+    }
+    config.insert("access-token".into(), access_token.clone());
+    Ok(access_token)
+}
+
+/// Has the gcp `expiry` timestamp (RFC3339) already passed? A missing or
+/// unparseable expiry is treated as expired so we re-run the helper.
+fn gcp_expired(expiry: Option<&str>) -> bool {
+    match expiry.and_then(|e| chrono::DateTime::parse_from_rfc3339(e).ok()) {
+        // refresh a little early to avoid racing the expiry on the wire
+        Some(t) => t.with_timezone(&chrono::Utc) - chrono::Duration::seconds(60) <= chrono::Utc::now(),
+        None => true,
+    }
+}
+
+/// Return the current Azure `access-token`, refreshing it through an OAuth2
+/// refresh-token grant against the tenant's login endpoint when it has expired.
+async fn azure_token(config: &mut HashMap<String, String>) -> Result<String> {
+    if let Some(access_token) = config.get("access-token") {
+        if !access_token.is_empty() && !is_expired(access_token)? {
+            return Ok(access_token.clone());
+        }
+    }
+
+    let refresh_token = config
+        .get("refresh-token")
+        .ok_or_else(|| Error::KubeConfig("azure auth-provider is missing refresh-token".into()))?
+        .clone();
+    let client_id = config
+        .get("client-id")
+        .ok_or_else(|| Error::KubeConfig("azure auth-provider is missing client-id".into()))?
+        .clone();
+    let tenant_id = config
+        .get("tenant-id")
+        .ok_or_else(|| Error::KubeConfig("azure auth-provider is missing tenant-id".into()))?
+        .clone();
+    let apiserver_id = config
+        .get("apiserver-id")
+        .ok_or_else(|| Error::KubeConfig("azure auth-provider is missing apiserver-id".into()))?
+        .clone();
+
+    let token_endpoint = format!("https://login.microsoftonline.com/{}/oauth2/token", tenant_id);
+    let params = [
+        ("grant_type", "refresh_token"),
+        ("refresh_token", refresh_token.as_str()),
+        ("client_id", client_id.as_str()),
+        ("resource", apiserver_id.as_str()),
+    ];
+    let res: HashMap<String, serde_json::Value> = reqwest::Client::new()
+        .post(&token_endpoint)
+        .form(&params)
+        .send()
+        .await
+        .map_err(|e| Error::KubeConfig(format!("azure token refresh request failed: {}", e)))?
+        .json()
+        .await
+        .map_err(|e| Error::KubeConfig(format!("azure token refresh response was not json: {}", e)))?;
+
+    let access_token = res
+        .get("access_token")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_owned())
+        .ok_or_else(|| Error::KubeConfig("azure token refresh response did not contain an access_token".into()))?;
+    config.insert("access-token".into(), access_token.clone());
+    Ok(access_token)
+}
+
+/// Resolve a `{.a.b.c}` style key path against a JSON value.
+fn extract_key(json: &serde_json::Value, key: &str) -> Option<String> {
+    let trimmed = key.trim_start_matches("{.").trim_end_matches('}');
+    let mut cur = json;
+    for segment in trimmed.split('.').filter(|s| !s.is_empty()) {
+        cur = cur.get(segment)?;
+    }
+    cur.as_str().map(|s| s.to_owned())
+}
+
+/// Has the `exp` claim of a JWT already passed?
+fn is_expired(jwt: &str) -> Result<bool> {
+    let payload = jwt
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| Error::KubeConfig("id-token is not a well formed jwt".into()))?;
+    let decoded = base64::decode_config(payload, base64::URL_SAFE_NO_PAD)
+        .map_err(|e| Error::KubeConfig(format!("could not decode jwt payload: {}", e)))?;
+    let claims: HashMap<String, serde_json::Value> = serde_json::from_slice(&decoded)
+        .map_err(|e| Error::KubeConfig(format!("could not parse jwt claims: {}", e)))?;
+
+    let exp = claims
+        .get("exp")
+        .and_then(|v| v.as_i64())
+        .ok_or_else(|| Error::KubeConfig("jwt is missing an exp claim".into()))?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    // refresh a little early to avoid racing the expiry on the wire
+    Ok(exp - 60 <= now)
+}
@@ -6,6 +6,7 @@
 //! The full `Config` and child-objects are exposed here for convenience only.
 
 mod apis;
+mod auth_provider;
 mod exec;
 pub mod incluster_config;
 pub(crate) mod kube_config;
@@ -94,6 +95,21 @@ pub struct ConfigOptions {
     pub context: Option<String>,
     pub cluster: Option<String>,
     pub user: Option<String>,
+    /// Subject to act as via `Impersonate-*` headers, if any.
+    pub impersonate: Option<ImpersonationConfig>,
+}
+
+/// A user to impersonate, sent alongside the primary credentials.
+///
+/// Mirrors the `--as`, `--as-group` and `--as-extra` flags of `kubectl`.
+#[derive(Default, Clone, Debug)]
+pub struct ImpersonationConfig {
+    /// The user to act as (`Impersonate-User`).
+    pub user: String,
+    /// Groups to act as (`Impersonate-Group`, repeatable).
+    pub groups: Vec<String>,
+    /// Extra attributes to carry (`Impersonate-Extra-<key>`, repeatable per key).
+    pub extra: std::collections::HashMap<String, Vec<String>>,
 }
 
 #[derive(Debug)]
@@ -109,7 +125,7 @@ pub struct ClientConfig {
 impl ClientConfig {
     pub async fn infer() -> Result<Self> {
         let config = Configuration::infer().await?;
-        match Self::new_from_cluster_env(config) {
+        match Self::new_from_cluster_env(config, &ConfigOptions::default()) {
             Err(e) => {
                 trace!("No in-cluster config found: {}", e);
                 trace!("Falling back to local kube config");
@@ -119,7 +135,7 @@ impl ClientConfig {
         }
     }
 
-    pub fn new_from_cluster_env(config: Configuration) -> Result<Self> {
+    pub fn new_from_cluster_env(config: Configuration, options: &ConfigOptions) -> Result<Self> {
         let root_cert = incluster_config::load_cert()?;
 
         let token = incluster_config::load_token()
@@ -132,6 +148,10 @@ impl ClientConfig {
                 .map_err(|e| Error::KubeConfig(format!("Invalid bearer token: {}", e)))?,
         );
 
+        if let Some(impersonate) = &options.impersonate {
+            apply_impersonation(&mut headers, impersonate)?;
+        }
+
         Ok(Self {
             cluster_url: config.cluster_url,
             root_cert: Some(root_cert),
@@ -147,17 +167,23 @@ impl ClientConfig {
     /// This allows to create your custom reqwest client for using with the cluster API.
     pub async fn new_from_kube_config(options: &ConfigOptions) -> Result<Self> {
         let configuration = Configuration::new_from_kube_config(&options).await?;
-        let loader = ConfigLoader::new_from_options(&options).await?;
+        let mut loader = ConfigLoader::new_from_options(&options).await?;
 
+        let mut exec_identity = None;
         let token = match &loader.user.token {
             Some(token) => Some(token.clone()),
             None => {
                 if let Some(exec) = &loader.user.exec {
-                    let creds = exec::auth_exec(exec)?;
-                    let status = creds.status.ok_or_else(|| {
-                        Error::KubeConfig("exec-plugin response did not contain a status".into())
-                    })?;
-                    status.token
+                    let creds = exec_credential(exec, &loader.cluster)?;
+                    match (&creds.client_certificate_data, &creds.client_key_data) {
+                        (Some(cert), Some(key)) => {
+                            exec_identity = Some(exec_identity_from(cert, key)?);
+                            None
+                        }
+                        _ => creds.token,
+                    }
+                } else if let Some(provider) = &mut loader.user.auth_provider {
+                    auth_provider::token(provider).await?
                 } else {
                     None
                 }
@@ -167,7 +193,8 @@ impl ClientConfig {
         let timeout = std::time::Duration::new(295, 0);
         let mut accept_invalid_certs = false;
         let mut root_cert = None;
-        let mut identity = None;
+        // an exec plugin may hand back a client certificate instead of a token
+        let mut identity = exec_identity;
 
         if let Some(ca_bundle) = loader.ca_bundle()? {
             use std::convert::TryInto;
@@ -177,13 +204,15 @@ impl ClientConfig {
             }
         }
 
-        match loader.identity(" ") {
-            Ok(id) => identity = Some(id),
-            Err(e) => {
-                debug!("failed to load client identity from kube config: {}", e);
-                // last resort only if configs ask for it, and no client certs
-                if let Some(true) = loader.cluster.insecure_skip_tls_verify {
-                    accept_invalid_certs = true;
+        if identity.is_none() {
+            match loader.identity(" ") {
+                Ok(id) => identity = Some(id),
+                Err(e) => {
+                    debug!("failed to load client identity from kube config: {}", e);
+                    // last resort only if configs ask for it, and no client certs
+                    if let Some(true) = loader.cluster.insecure_skip_tls_verify {
+                        accept_invalid_certs = true;
+                    }
                 }
             }
         }
@@ -212,6 +241,10 @@ impl ClientConfig {
             _ => {}
         }
 
+        if let Some(impersonate) = &options.impersonate {
+            apply_impersonation(&mut headers, impersonate)?;
+        }
+
         Ok(Self {
             cluster_url: configuration.cluster_url,
             root_cert,
@@ -223,6 +256,140 @@ impl ClientConfig {
     }
 }
 
+/// Inject `Impersonate-User`, `Impersonate-Group` and `Impersonate-Extra-<key>`
+/// headers describing the subject to act as.
+fn apply_impersonation(
+    headers: &mut reqwest::header::HeaderMap,
+    impersonate: &ImpersonationConfig,
+) -> Result<()> {
+    use reqwest::header::{HeaderName, HeaderValue};
+
+    headers.insert(
+        "Impersonate-User",
+        HeaderValue::from_str(&impersonate.user)
+            .map_err(|e| Error::KubeConfig(format!("Invalid impersonate user: {}", e)))?,
+    );
+    for group in &impersonate.groups {
+        headers.append(
+            "Impersonate-Group",
+            HeaderValue::from_str(group)
+                .map_err(|e| Error::KubeConfig(format!("Invalid impersonate group: {}", e)))?,
+        );
+    }
+    for (key, values) in &impersonate.extra {
+        let name = HeaderName::from_bytes(format!("Impersonate-Extra-{}", key).as_bytes())
+            .map_err(|e| Error::KubeConfig(format!("Invalid impersonate extra key: {}", e)))?;
+        for value in values {
+            headers.append(
+                name.clone(),
+                HeaderValue::from_str(value)
+                    .map_err(|e| Error::KubeConfig(format!("Invalid impersonate extra value: {}", e)))?,
+            );
+        }
+    }
+    Ok(())
+}
+
+use chrono::{DateTime, Utc};
+
+// Exec-plugin results cached per exec config and cluster so a single plugin invocation can
+// be reused for the life of the credential it returns.
+lazy_static::lazy_static! {
+    static ref EXEC_CACHE: std::sync::Mutex<std::collections::HashMap<String, CachedCredential>> =
+        std::sync::Mutex::new(std::collections::HashMap::new());
+}
+
+#[derive(Clone)]
+struct CachedCredential {
+    token: Option<String>,
+    client_certificate_data: Option<String>,
+    client_key_data: Option<String>,
+    expiration_timestamp: Option<DateTime<Utc>>,
+}
+
+/// Run (or reuse a cached run of) an exec credential plugin.
+///
+/// The plugin is handed the requested cluster info through `KUBERNETES_EXEC_INFO`
+/// per the `client.authentication.k8s.io` contract, and its answer is cached until
+/// the `status.expirationTimestamp` it reports has passed.
+fn exec_credential(exec: &ExecConfig, cluster: &Cluster) -> Result<CachedCredential> {
+    // The plugin may mint cluster-specific credentials off an otherwise-identical
+    // exec block, so the cluster (its server) has to be part of the cache key.
+    let key = format!("{}|{:?}", cluster.server, exec);
+    if let Some(cached) = EXEC_CACHE.lock().unwrap().get(&key) {
+        if cached.expiration_timestamp.map(|e| e > Utc::now()).unwrap_or(false) {
+            return Ok(cached.clone());
+        }
+    }
+
+    // Hand the cluster info to the plugin through *its* environment rather than
+    // the process-wide env, so concurrent loads for different clusters can't race
+    // on the shared variable and it doesn't leak into unrelated child processes.
+    let mut exec_with_info = exec.clone();
+    let mut info_env = std::collections::HashMap::new();
+    info_env.insert("name".to_owned(), "KUBERNETES_EXEC_INFO".to_owned());
+    info_env.insert("value".to_owned(), exec_info(cluster)?);
+    exec_with_info.env.get_or_insert_with(Vec::new).push(info_env);
+    let creds = exec::auth_exec(&exec_with_info)?;
+    let status = creds
+        .status
+        .ok_or_else(|| Error::KubeConfig("exec-plugin response did not contain a status".into()))?;
+
+    let expiration_timestamp = status
+        .expiration_timestamp
+        .as_deref()
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        .map(|t| t.with_timezone(&Utc));
+    let cached = CachedCredential {
+        token: status.token,
+        client_certificate_data: status.client_certificate_data,
+        client_key_data: status.client_key_data,
+        expiration_timestamp,
+    };
+
+    EXEC_CACHE.lock().unwrap().insert(key, cached.clone());
+    Ok(cached)
+}
+
+/// Render the `KUBERNETES_EXEC_INFO` payload describing the cluster the plugin
+/// is being asked to authenticate against.
+fn exec_info(cluster: &Cluster) -> Result<String> {
+    // Report interactive mode so plugins that need to prompt for login know
+    // whether a terminal is attached to talk to. Derived via std rather than a
+    // new crate dependency.
+    use std::io::IsTerminal;
+    let interactive = std::io::stdin().is_terminal();
+    let info = serde_json::json!({
+        "apiVersion": "client.authentication.k8s.io/v1beta1",
+        "kind": "ExecCredential",
+        "spec": {
+            "interactive": interactive,
+            "cluster": {
+                "server": cluster.server,
+                "certificate-authority-data": cluster.certificate_authority_data,
+                "insecure-skip-tls-verify": cluster.insecure_skip_tls_verify,
+            },
+        },
+    });
+    serde_json::to_string(&info)
+        .map_err(|e| Error::KubeConfig(format!("could not serialize exec info: {}", e)))
+}
+
+/// Build a `reqwest::Identity` from the PEM certificate/key pair an exec plugin
+/// returned instead of a token.
+fn exec_identity_from(cert: &str, key: &str) -> Result<reqwest::Identity> {
+    let mut pem = Vec::with_capacity(cert.len() + key.len() + 1);
+    pem.extend_from_slice(cert.as_bytes());
+    // ensure the cert and key blocks are newline-separated so the concatenation
+    // is still valid PEM when the cert data has no trailing newline
+    if !cert.ends_with('\n') {
+        pem.push(b'\n');
+    }
+    pem.extend_from_slice(key.as_bytes());
+    reqwest::Identity::from_pem(&pem)
+        .map_err(|e| Error::KubeConfig(format!("could not build identity from exec credential: {}", e)))
+}
+
 // temporary catalina hack for openssl only
 #[cfg(all(target_os = "macos", feature = "native-tls"))]
 fn hacky_cert_lifetime_for_macos(ca: &Der) -> bool {